@@ -3,10 +3,14 @@ use leaprs::*;
 use midir::{MidiOutput, MidiOutputConnection};
 use mouse_rs::Mouse;
 use std::{
-    collections::HashMap,
     error::Error,
+    fs::File,
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use crossterm::{
     execute,
     terminal::{Clear, ClearType},
@@ -26,13 +30,30 @@ const MAX_Z: f32 = 0.0;
 const MIN_Z: f32 = -100.0;
 
 const MIDI_CHANNEL: u8 = 2;
-const MIDI_DELAY_MS: u64 = 1000;
 const MOVING_AVERAGE_SAMPLES: usize = 3;
 //const MOVEMENT_THRESHOLD: i32 = 300;
 
 const BASE_NOTE: u8 = 42; // MIDI note value for F#2
 const OCTAVE_SIZE: u8 = 12;
 
+const LEFT_MIDI_CHANNEL: u8 = 3; // left-hand control/modulation layer
+const ARP_TOGGLE_Z: i32 = -60; // left-hand Z depth that flips the arpeggiator
+
+const METRONOME_CHANNEL: u8 = 10; // GM percussion channel for the click
+const CLICK_NOTE: u8 = 37; // side-stick / click
+const DEFAULT_BPM: u32 = 120;
+const DEFAULT_SUBDIVISION: u32 = 4; // grid ticks per beat
+
+// Pitch-bend expression settings
+const PITCH_BEND_RANGE_CENTS: f32 = 200.0; // +/- 2 semitones of travel
+const PITCH_BEND_DEAD_ZONE_CENTS: f32 = 6.0; // ignore jitter near centre
+const PITCH_BEND_SLEW_CENTS: f32 = 25.0; // max change applied per frame
+
+// Standard MIDI File recording settings
+const RECORD_PPQ: u16 = 480; // pulses (ticks) per quarter note
+const RECORD_TEMPO_BPM: u32 = 120;
+const RECORDING_PATH: &str = "recording.mid";
+
 fn generate_minor_scale(octaves: u8) -> Vec<u8> {
     let mut scale = Vec::new();
     for octave in 0..octaves {
@@ -123,8 +144,413 @@ fn map_to_midi(value: f32, leap_min: f32, leap_max: f32, midi_range: f32) -> u8
     return midi_value.clamp(0, 127);
 }
 
+/// Captures every MIDI message sent to the output port, timestamped, so the
+/// improvisation can be written out as a Standard MIDI File on exit.
+struct MidiRecording {
+    /// MTrk event stream: each event prefixed by its delta-time as a
+    /// variable-length quantity, in the order it was sent.
+    data: Vec<u8>,
+    last_event_time: Instant,
+}
+
+impl MidiRecording {
+    fn new() -> Self {
+        MidiRecording {
+            data: Vec::new(),
+            last_event_time: Instant::now(),
+        }
+    }
+
+    /// Append the elapsed time since the previous event as a MIDI
+    /// variable-length quantity (7 bits per byte, high bit set on every byte
+    /// but the last).
+    fn push_delta(&mut self, delta: u32) {
+        // A 32-bit value needs up to five 7-bit groups.
+        let mut buffer = [0u8; 5];
+        let mut count = 0;
+        let mut value = delta;
+        loop {
+            buffer[count] = (value & 0x7f) as u8;
+            count += 1;
+            value >>= 7;
+            if value == 0 {
+                break;
+            }
+        }
+        // Emit most-significant group first, high bit set on all but the last.
+        for i in (0..count).rev() {
+            let last = i == 0;
+            self.data
+                .push(buffer[i] | if last { 0x00 } else { 0x80 });
+        }
+    }
+
+    /// Record a raw MIDI message, converting the wall-clock delay since the
+    /// previous event into ticks using the chosen PPQ and tempo.
+    fn record(&mut self, now: Instant, message: &[u8]) {
+        let elapsed = now.duration_since(self.last_event_time).as_secs_f64();
+        let ticks = elapsed * (RECORD_TEMPO_BPM as f64) / 60.0 * (RECORD_PPQ as f64);
+        self.push_delta(ticks.round() as u32);
+        self.data.extend_from_slice(message);
+        self.last_event_time = now;
+    }
+
+    /// Write a type-0 Standard MIDI File containing the captured events.
+    fn write_to_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = File::create(path)?;
+
+        // MThd: format 0, one track, division = PPQ.
+        file.write_all(b"MThd")?;
+        file.write_all(&6u32.to_be_bytes())?;
+        file.write_all(&0u16.to_be_bytes())?; // format 0
+        file.write_all(&1u16.to_be_bytes())?; // single track
+        file.write_all(&RECORD_PPQ.to_be_bytes())?;
+
+        // Track body: tempo meta event, captured events, end of track.
+        let mut track = Vec::new();
+        let micros_per_quarter = 60_000_000 / RECORD_TEMPO_BPM;
+        track.push(0x00); // delta 0
+        track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+        track.extend_from_slice(&micros_per_quarter.to_be_bytes()[1..]); // 24-bit
+        track.extend_from_slice(&self.data);
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]); // end of track
+
+        file.write_all(b"MTrk")?;
+        file.write_all(&(track.len() as u32).to_be_bytes())?;
+        file.write_all(&track)?;
+        Ok(())
+    }
+}
+
+/// Thin wrapper around the output connection that forwards every message to
+/// the port and, when recording is enabled, into a [`MidiRecording`].
+struct Recorder {
+    output_port: MidiOutputConnection,
+    recording: Option<MidiRecording>,
+}
+
+impl Recorder {
+    fn new(output_port: MidiOutputConnection, record: bool) -> Self {
+        Recorder {
+            output_port,
+            recording: if record {
+                Some(MidiRecording::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.record(Instant::now(), message);
+        }
+        self.output_port.send(message)?;
+        Ok(())
+    }
+
+    /// Flush the captured performance to a `.mid` file if recording was on.
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(recording) = self.recording.as_ref() {
+            recording.write_to_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scale-degree offsets above the root that make up a chord voicing. Each
+/// slot corresponds to one bit in a configuration mask, so up to this many
+/// voices can sound at once.
+const CHORD_VOICES: [usize; 4] = [6, 0, 2, 4];
+/// Default configuration: every voice sounding.
+const FULL_CHORD_CONFIG: u16 = (1 << CHORD_VOICES.len()) - 1;
+
+/// Stateful chord filter that morphs the sounding chord as the root or the
+/// voice configuration glides, emitting only the note-on/note-off deltas
+/// needed instead of retriggering every voice on each update.
+struct ChordEngine {
+    channel: u8,
+    /// Pitch currently sounding for each voice slot, if any.
+    voice_pitch: [Option<u8>; CHORD_VOICES.len()],
+    /// Velocity last used for each voice, reused when the voice is re-sounded.
+    held_velocity: [u8; CHORD_VOICES.len()],
+    /// Configuration mask applied on the previous update.
+    prev_config: u16,
+}
+
+impl ChordEngine {
+    fn new(channel: u8) -> Self {
+        ChordEngine {
+            channel,
+            voice_pitch: [None; CHORD_VOICES.len()],
+            held_velocity: [0; CHORD_VOICES.len()],
+            prev_config: 0,
+        }
+    }
+
+    /// Resolve the sounding pitch for one voice of a chord rooted at `note`,
+    /// clamped to the bounds of the scale table.
+    fn voice_pitch_for(note: u8, offset: usize, scale: &[u8]) -> u8 {
+        let root_index = scale.binary_search(&note).unwrap_or(0);
+        let index = (root_index + offset).min(scale.len() - 1);
+        scale[index]
+    }
+
+    /// Move the sounding chord towards `note`/`config`, reusing `velocity` for
+    /// any voice that newly sounds. Voices whose pitch is unchanged are left
+    /// untouched so held notes do not machine-gun retrigger.
+    fn update(
+        &mut self,
+        output_port: &mut Recorder,
+        note: u8,
+        velocity: u8,
+        config: u16,
+        scale: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let note_on_status = 0x90 | (self.channel - 1);
+        let note_off_status = 0x80 | (self.channel - 1);
+
+        for (i, &offset) in CHORD_VOICES.iter().enumerate() {
+            let bit = 1u16 << i;
+            let was_on = self.prev_config & bit != 0;
+            let now_on = config & bit != 0;
+            let new_pitch = Self::voice_pitch_for(note, offset, scale);
+            let old_pitch = self.voice_pitch[i];
+
+            if now_on {
+                self.held_velocity[i] = velocity;
+            }
+
+            match (was_on, now_on) {
+                // Voice stays silent.
+                (false, false) => {}
+                // Voice turns off: release whatever it was sounding.
+                (true, false) => {
+                    if let Some(pitch) = old_pitch {
+                        output_port.send(&[note_off_status, pitch, 0])?;
+                    }
+                    self.voice_pitch[i] = None;
+                }
+                // Voice turns on: sound the new pitch with the stored velocity.
+                (false, true) => {
+                    output_port.send(&[note_on_status, new_pitch, self.held_velocity[i]])?;
+                    self.voice_pitch[i] = Some(new_pitch);
+                }
+                // Voice stays on: only re-voice if its pitch actually moved.
+                (true, true) => {
+                    if old_pitch != Some(new_pitch) {
+                        if let Some(pitch) = old_pitch {
+                            output_port.send(&[note_off_status, pitch, 0])?;
+                        }
+                        output_port.send(&[note_on_status, new_pitch, self.held_velocity[i]])?;
+                        self.voice_pitch[i] = Some(new_pitch);
+                    }
+                }
+            }
+        }
+
+        self.prev_config = config;
+        Ok(())
+    }
+
+    /// Release every sounding voice (used on shutdown).
+    fn all_notes_off(&mut self, output_port: &mut Recorder) -> Result<(), Box<dyn Error>> {
+        let note_off_status = 0x80 | (self.channel - 1);
+        for slot in self.voice_pitch.iter_mut() {
+            if let Some(pitch) = slot.take() {
+                output_port.send(&[note_off_status, pitch, 0])?;
+            }
+        }
+        self.prev_config = 0;
+        Ok(())
+    }
+}
+
+/// Selectable response shapes applied to velocity and CC values. Each maps a
+/// 0–127 input to a 0–127 output so the linear `map_to_midi` result can be
+/// reshaped before it reaches a velocity or CC byte.
+enum VelocityCurve {
+    Linear,
+    Exponential,
+    Logarithmic,
+    /// Precomputed 128-entry lookup table.
+    Table(Box<[u8; 128]>),
+}
+
+impl VelocityCurve {
+    /// Pick a curve by name, falling back to linear for anything unknown.
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "exp" | "exponential" => VelocityCurve::Exponential,
+            "log" | "logarithmic" => VelocityCurve::Logarithmic,
+            "table" => VelocityCurve::Table(Box::new(compressive_table())),
+            _ => VelocityCurve::Linear,
+        }
+    }
+
+    /// Reshape a 0–127 input into a 0–127 output.
+    fn apply(&self, input: u8) -> u8 {
+        let x = (input.min(127) as f32) / 127.0;
+        let y = match self {
+            VelocityCurve::Linear => x,
+            VelocityCurve::Exponential => x * x,
+            VelocityCurve::Logarithmic => x.sqrt(),
+            VelocityCurve::Table(table) => return table[input.min(127) as usize],
+        };
+        (y * 127.0).round().clamp(0.0, 127.0) as u8
+    }
+}
+
+/// Build a gently compressive lookup table: low input values are spread out
+/// and high values compress towards 127, mirroring the DMX MIDI-volume-to-
+/// level mapping used by the OPL/OPN players.
+fn compressive_table() -> [u8; 128] {
+    let mut table = [0u8; 128];
+    for (input, slot) in table.iter_mut().enumerate() {
+        let x = (input as f32) / 127.0;
+        *slot = (x.powf(0.6) * 127.0).round().clamp(0.0, 127.0) as u8;
+    }
+    table
+}
+
+/// Continuous 14-bit pitch-bend driven by palm roll/tilt and fine X motion.
+/// State is tracked in cents (like progmidi's `pitch_bend`) with a dead-zone
+/// around centre and per-frame slew limiting so the moving-average jitter does
+/// not produce audible wobble.
+struct PitchBend {
+    channel: u8,
+    range_cents: f32,
+    cents: f32,
+}
+
+impl PitchBend {
+    fn new(channel: u8, range_cents: f32) -> Self {
+        PitchBend {
+            channel,
+            range_cents,
+            cents: 0.0,
+        }
+    }
+
+    /// Glide towards the deviation (normalised to `-1.0..=1.0`) and emit a
+    /// pitch-bend message. Returns without sending only on a port error.
+    fn update(&mut self, output_port: &mut Recorder, deviation: f32) -> Result<(), Box<dyn Error>> {
+        let mut target = deviation.clamp(-1.0, 1.0) * self.range_cents;
+        if target.abs() < PITCH_BEND_DEAD_ZONE_CENTS {
+            target = 0.0;
+        }
+        // Slew-limit the approach so small frame-to-frame changes stay smooth.
+        let delta = (target - self.cents).clamp(-PITCH_BEND_SLEW_CENTS, PITCH_BEND_SLEW_CENTS);
+        self.cents += delta;
+
+        let value = self.as_14bit();
+        let status = 0xE0 | (self.channel - 1);
+        output_port.send(&[status, (value & 0x7f) as u8, ((value >> 7) & 0x7f) as u8])?;
+        Ok(())
+    }
+
+    /// Current bend as a 14-bit value centred on `0x2000`.
+    fn as_14bit(&self) -> u16 {
+        let normalised = (self.cents / self.range_cents).clamp(-1.0, 1.0);
+        let value = 0x2000 as f32 + normalised * 0x1fff as f32;
+        (value.round() as i32).clamp(0, 0x3fff) as u16
+    }
+}
+
+/// Tempo reference driving the beat clock and the audible click.
+struct Metronome {
+    bpm: u32,
+    key: u8,
+    volume: u8,
+}
+
+impl Metronome {
+    fn new(bpm: u32) -> Self {
+        Metronome {
+            bpm,
+            key: CLICK_NOTE,
+            volume: 100,
+        }
+    }
+
+    /// Sound a short click on the dedicated percussion channel.
+    fn click(&self, output_port: &mut Recorder) -> Result<(), Box<dyn Error>> {
+        let note_on = 0x90 | (METRONOME_CHANNEL - 1);
+        let note_off = 0x80 | (METRONOME_CHANNEL - 1);
+        output_port.send(&[note_on, self.key, self.volume])?;
+        output_port.send(&[note_off, self.key, 0])?;
+        Ok(())
+    }
+}
+
+/// Beat clock that divides each beat into `subdivision` grid ticks and reports
+/// the boundaries crossed, so generated notes can be quantized in time.
+struct BeatClock {
+    start: Instant,
+    tick: Duration,
+    subdivision: u32,
+    last_index: u64,
+}
+
+impl BeatClock {
+    fn new(metronome: &Metronome, subdivision: u32) -> Self {
+        let beat = Duration::from_secs_f64(60.0 / metronome.bpm.max(1) as f64);
+        BeatClock {
+            start: Instant::now(),
+            tick: beat / subdivision.max(1),
+            subdivision: subdivision.max(1),
+            last_index: 0,
+        }
+    }
+
+    /// Advance the clock to `now`, returning each newly-crossed grid tick as
+    /// `(index, is_beat)`.
+    fn advance(&mut self, now: Instant) -> Vec<(u64, bool)> {
+        let current =
+            (now.duration_since(self.start).as_secs_f64() / self.tick.as_secs_f64()) as u64;
+        let mut ticks = Vec::new();
+        while self.last_index < current {
+            self.last_index += 1;
+            let is_beat = self.last_index % self.subdivision as u64 == 0;
+            ticks.push((self.last_index, is_beat));
+        }
+        ticks
+    }
+}
+
+/// A note computed from the hand pose, buffered until the next grid tick.
+struct PendingNote {
+    note: u8,
+    velocity: u8,
+    depth: u8,
+    duration: u64,
+    program: u8,
+    config: u16,
+}
+
+/// Per-hand tracking state on an independent MIDI channel, shared by the
+/// melody (right) and control (left) pipelines. Voice-specific machinery like
+/// the chord engine and pitch bend lives with whichever pipeline uses it,
+/// rather than being carried unused on the left hand.
+struct HandVoice {
+    channel: u8,
+    moving_average: MovingAverage,
+    last_note_time: Instant,
+}
+
+impl HandVoice {
+    fn new(channel: u8) -> Self {
+        HandVoice {
+            channel,
+            moving_average: MovingAverage::new(),
+            last_note_time: Instant::now(),
+        }
+    }
+}
+
 fn send_midi_chord_on(
-    output_port: &mut MidiOutputConnection,
+    output_port: &mut Recorder,
+    chord_engine: &mut ChordEngine,
     note: u8,
     velocity: u8,
     program: u8,
@@ -132,9 +558,10 @@ fn send_midi_chord_on(
     decay: u8,
     sustain: u8,
     release: u8,
+    config: u16,
     scale: &Vec<u8>,
 ) -> Result<(), Box<dyn Error>> {
-    let program_change_status = 0xC0 | (MIDI_CHANNEL - 1);
+    let program_change_status = 0xC0 | (chord_engine.channel - 1);
     output_port.send(&[program_change_status, program])?;
 
     send_midi_cc(output_port, 1, attack)?;
@@ -142,41 +569,33 @@ fn send_midi_chord_on(
     send_midi_cc(output_port, 3, sustain)?;
     send_midi_cc(output_port, 4, release)?;
 
-    for &offset in &[6, 0, 2, 4] {
-        // Send MIDI note-on messages for the notes of the chord
-        
-        let scale_index = scale.binary_search(&note).unwrap();
-        let chord_note = scale[scale_index + offset];
-        println!("note: {:?}, chord_note {}, {:?}", note, chord_note, midi_to_note_name(chord_note).unwrap());
-        let note_on_status = 0x90 | (MIDI_CHANNEL - 1);
-        output_port.send(&[note_on_status, chord_note, velocity])?;
-    }
+    // Morph the sounding chord to the new root instead of retriggering it.
+    chord_engine.update(output_port, note, velocity, config, scale)?;
 
     Ok(())
 }
 
-fn send_midi_note_off(
-    output_port: &mut MidiOutputConnection,
-    note: u8,
-    velocity: u8,
+fn send_midi_cc(
+    output_port: &mut Recorder,
+    cc_number: u8,
+    value: u8,
 ) -> Result<(), Box<dyn Error>> {
-    let note_off_status = 0x80 | (MIDI_CHANNEL - 1);
-    output_port.send(&[note_off_status, note, velocity])?;
-    Ok(())
+    send_midi_cc_on(output_port, MIDI_CHANNEL, cc_number, value)
 }
 
-fn send_midi_cc(
-    output_port: &mut MidiOutputConnection,
+fn send_midi_cc_on(
+    output_port: &mut Recorder,
+    channel: u8,
     cc_number: u8,
     value: u8,
 ) -> Result<(), Box<dyn Error>> {
-    let cc_status = 0xB0 | (MIDI_CHANNEL - 1);
+    let cc_status = 0xB0 | (channel - 1);
     output_port.send(&[cc_status, cc_number, value])?;
     Ok(())
 }
 
 fn change_instrument(
-    output_port: &mut MidiOutputConnection,
+    output_port: &mut Recorder,
     channel: u8,
     program: u8,
 ) -> Result<(), Box<dyn Error>> {
@@ -192,6 +611,259 @@ fn change_instrument(
     Ok(())
 }
 
+/// MIDI key the loaded SoundFont sample is assumed to be recorded at; other
+/// keys are resampled relative to it.
+const SF2_ROOT_KEY: u8 = 60;
+
+/// Minimal SoundFont: the 16-bit PCM block from an `.sf2` file's `smpl`
+/// sub-chunk, loaded once and shared between all sounding voices.
+///
+/// This is a deliberate toy loader. It grabs the single concatenated `smpl`
+/// sample pool and ignores the `shdr` headers that carve it into individual
+/// instrument samples (start/end offsets, loop points, per-sample root keys).
+/// Every voice therefore plays from the start of the whole pool, pitched
+/// relative to [`SF2_ROOT_KEY`], rather than the note's own recorded sample.
+struct SoundFont {
+    samples: Arc<Vec<f32>>,
+}
+
+impl SoundFont {
+    /// Load the `smpl` sample data out of a RIFF `.sf2` file. See the struct
+    /// docs for the limitations of this simplified loader.
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = std::fs::read(path)?;
+        let pcm = Self::find_chunk(&bytes, b"smpl").ok_or("no smpl chunk in SoundFont")?;
+        let samples: Vec<f32> = pcm
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / i16::MAX as f32)
+            .collect();
+        Ok(SoundFont {
+            samples: Arc::new(samples),
+        })
+    }
+
+    /// Walk the RIFF/LIST chunk tree looking for a four-byte chunk id.
+    fn find_chunk<'a>(bytes: &'a [u8], id: &[u8; 4]) -> Option<&'a [u8]> {
+        // Skip the outer "RIFF" header (12 bytes) and scan sub-chunks.
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let chunk_id = &bytes[pos..pos + 4];
+            let size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().ok()?) as usize;
+            let body = pos + 8;
+            if chunk_id == b"LIST" {
+                // Recurse into the list, skipping its four-byte form type.
+                // The list header ("LIST", size, form type) is 12 bytes, the
+                // same layout as the RIFF header the recursive call skips.
+                if let Some(found) = Self::find_chunk(&bytes[pos..(body + size).min(bytes.len())], id) {
+                    return Some(found);
+                }
+            } else if chunk_id == id {
+                return bytes.get(body..(body + size).min(bytes.len()));
+            }
+            pos = body + size + (size & 1); // chunks are word-aligned
+        }
+        None
+    }
+}
+
+/// Linear ADSR envelope with an explicit hold time and a `set_falloff` release
+/// ramp, mirroring progmidi's per-note shaping.
+struct Adsr {
+    attack: f32,
+    decay: f32,
+    sustain: f32,
+    hold: f32,
+    falloff_start: f32,
+    falloff_rate: f32,
+    elapsed: f32,
+    releasing_at: Option<f32>,
+}
+
+impl Adsr {
+    fn new(attack: u8, decay: u8, sustain: u8, release: u8) -> Self {
+        Adsr {
+            attack: attack as f32 / 127.0,
+            decay: decay as f32 / 127.0,
+            sustain: sustain as f32 / 127.0,
+            hold: release as f32 / 127.0,
+            falloff_start: sustain as f32 / 127.0,
+            falloff_rate: (release.max(1) as f32) / 127.0,
+            elapsed: 0.0,
+            releasing_at: None,
+        }
+    }
+
+    fn set_hold_time(&mut self, seconds: f32) {
+        self.hold = seconds;
+    }
+
+    fn set_falloff(&mut self, start: f32, rate: f32) {
+        self.falloff_start = start;
+        self.falloff_rate = rate;
+    }
+
+    /// Amplitude at the current time; `None` once the release has fully decayed.
+    fn amplitude(&self) -> Option<f32> {
+        let t = self.elapsed;
+        if let Some(release_at) = self.releasing_at {
+            let level = self.falloff_start - (t - release_at) * self.falloff_rate;
+            return if level > 0.0 { Some(level) } else { None };
+        }
+        if t < self.attack {
+            Some(t / self.attack.max(1e-6))
+        } else if t < self.attack + self.decay {
+            let d = (t - self.attack) / self.decay.max(1e-6);
+            Some(1.0 - d * (1.0 - self.sustain))
+        } else if t < self.attack + self.decay + self.hold {
+            Some(self.sustain)
+        } else {
+            // Held past its hold time with no explicit note-off: fall off.
+            let over = t - (self.attack + self.decay + self.hold);
+            let level = self.sustain - over * self.falloff_rate;
+            if level > 0.0 {
+                Some(level)
+            } else {
+                None
+            }
+        }
+    }
+
+    fn release(&mut self) {
+        if self.releasing_at.is_none() {
+            self.releasing_at = Some(self.elapsed);
+        }
+    }
+}
+
+/// A single sounding voice built from a [`SamplesRequest`].
+struct Voice {
+    key: u8,
+    samples: Arc<Vec<f32>>,
+    position: f32,
+    step: f32,
+    volume: f32,
+    env: Adsr,
+}
+
+/// Builder describing a note to trigger on the audio backend, carrying key,
+/// velocity and the per-note envelope.
+struct SamplesRequest {
+    key: u8,
+    env: Adsr,
+    volume: f32,
+}
+
+impl SamplesRequest {
+    fn new(key: u8, velocity: u8, attack: u8, decay: u8, sustain: u8, release: u8) -> Self {
+        SamplesRequest {
+            key,
+            env: Adsr::new(attack, decay, sustain, release),
+            volume: velocity as f32 / 127.0,
+        }
+    }
+
+    fn set_hold_time(&mut self, seconds: f32) -> &mut Self {
+        self.env.set_hold_time(seconds);
+        self
+    }
+
+    fn set_volume(&mut self, volume: f32) -> &mut Self {
+        self.volume = volume;
+        self
+    }
+
+    fn set_falloff(&mut self, start: f32, rate: f32) -> &mut Self {
+        self.env.set_falloff(start, rate);
+        self
+    }
+}
+
+/// Internal synth: mixes all active voices into a stereo `cpal` output stream
+/// so the tool is audible without an external MIDI host.
+struct AudioBackend {
+    voices: Arc<Mutex<Vec<Voice>>>,
+    soundfont: SoundFont,
+    _stream: cpal::Stream,
+}
+
+impl AudioBackend {
+    fn new(soundfont: SoundFont) -> Result<Self, Box<dyn Error>> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or("no default audio output device")?;
+        let config = device.default_output_config()?;
+        let sample_rate = config.sample_rate().0 as f32;
+        let channels = config.channels() as usize;
+
+        let voices: Arc<Mutex<Vec<Voice>>> = Arc::new(Mutex::new(Vec::new()));
+        let render_voices = Arc::clone(&voices);
+        let dt = 1.0 / sample_rate;
+
+        let stream = device.build_output_stream(
+            &config.into(),
+            move |output: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut voices = render_voices.lock().unwrap();
+                for frame in output.chunks_mut(channels) {
+                    let mut mix = 0.0f32;
+                    for voice in voices.iter_mut() {
+                        voice.env.elapsed += dt;
+                        let Some(amp) = voice.env.amplitude() else {
+                            continue;
+                        };
+                        let index = voice.position as usize;
+                        if index + 1 < voice.samples.len() {
+                            mix += voice.samples[index] * amp * voice.volume;
+                            voice.position += voice.step;
+                        }
+                    }
+                    // Drop voices that have run out of samples or finished release.
+                    voices.retain(|v| {
+                        (v.position as usize) + 1 < v.samples.len()
+                            && v.env.amplitude().is_some()
+                    });
+                    let sample = mix.clamp(-1.0, 1.0);
+                    for out in frame.iter_mut() {
+                        *out = sample; // same signal to every channel (stereo)
+                    }
+                }
+            },
+            |err| eprintln!("audio stream error: {err}"),
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(AudioBackend {
+            voices,
+            soundfont,
+            _stream: stream,
+        })
+    }
+
+    /// Trigger a note, resampling the SoundFont sample to the requested key.
+    fn trigger(&self, request: SamplesRequest) {
+        // Playback ratio relative to the sample's recorded root key.
+        let step = 2f32.powf((request.key as f32 - SF2_ROOT_KEY as f32) / 12.0);
+        let voice = Voice {
+            key: request.key,
+            samples: Arc::clone(&self.soundfont.samples),
+            position: 0.0,
+            step,
+            volume: request.volume,
+            env: request.env,
+        };
+        self.voices.lock().unwrap().push(voice);
+    }
+
+    /// Move every voice sounding `key` into its release phase.
+    fn note_off(&self, key: u8) {
+        let mut voices = self.voices.lock().unwrap();
+        for voice in voices.iter_mut().filter(|v| v.key == key) {
+            voice.env.release();
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     // loggings
 
@@ -207,34 +879,110 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let mut program = 0;
 
-    let mut moving_average = MovingAverage::new();
-    let mut last_note_time = Instant::now();
+    // Startup options: `--curve <name>`, and `--audio <sf2>` to also render
+    // notes through the internal synth. MIDI output always runs; there is no
+    // option to turn it off, so audio plays alongside it, never alone.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let flag_value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+
+    let curve = VelocityCurve::from_name(
+        &flag_value("--curve").unwrap_or_else(|| "linear".to_string()),
+    );
+
+    let audio = match flag_value("--audio") {
+        Some(path) => Some(AudioBackend::new(SoundFont::load(&path)?)?),
+        None => None,
+    };
+
+    // Optional SMF recording: `--record [path]` (defaults to RECORDING_PATH).
+    let record_enabled = args.iter().any(|a| a == "--record");
+    let record_path = flag_value("--record")
+        .filter(|p| !p.starts_with("--"))
+        .unwrap_or_else(|| RECORDING_PATH.to_string());
+
+    // Tempo grid: notes are quantized to these subdivisions and a click sounds
+    // on every beat. `--bpm` and `--subdivision` tune it at startup.
+    let bpm = flag_value("--bpm")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BPM);
+    let subdivision = flag_value("--subdivision")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SUBDIVISION);
+    let metronome = Metronome::new(bpm);
+    let mut beat_clock = BeatClock::new(&metronome, subdivision);
+    let mut pending: Option<PendingNote> = None;
+
     let mut last_hand_position = (0, 0);
     let mut last_program_change_time = Instant::now();
 
+    // Per-hand voices: the right hand plays melody/chords, the left hand drives
+    // a separate control channel.
+    let mut right_voice = HandVoice::new(MIDI_CHANNEL);
+    let mut left_voice = HandVoice::new(LEFT_MIDI_CHANNEL);
+    // Melody-only machinery for the right hand.
+    let mut chord_engine = ChordEngine::new(MIDI_CHANNEL);
+    let mut pitch_bend = PitchBend::new(MIDI_CHANNEL, PITCH_BEND_RANGE_CENTS);
+    let mut arp_enabled = false;
+    let mut last_arp_toggle = Instant::now();
+
     let mouse = Mouse::new();
     let midi_out = MidiOutput::new("epicness")?;
     let output_ports = midi_out.ports();
-    let mut output_port: MidiOutputConnection = midi_out.connect(&output_ports[0], "epicness")?;
+    let connection_port: MidiOutputConnection = midi_out.connect(&output_ports[0], "epicness")?;
+    let mut output_port = Recorder::new(connection_port, record_enabled);
     change_instrument(&mut output_port, MIDI_CHANNEL, program).unwrap();
 
     let mut connection =
         Connection::create(ConnectionConfig::default()).expect("Failed to create connection");
     connection.open().expect("Failed to open the connection");
 
-    let mut active_notes: HashMap<u8, Instant> = HashMap::new();
+    // Flush the recording and shut down cleanly on Ctrl-C.
+    let running = Arc::new(AtomicBool::new(true));
+    let shutdown_flag = Arc::clone(&running);
+    ctrlc::set_handler(move || shutdown_flag.store(false, Ordering::SeqCst))
+        .expect("failed to set Ctrl-C handler");
 
-    loop {
+    // Pending audio releases: each rendered note is released `duration` ms
+    // after it is triggered.
+    let mut audio_off: Vec<(u8, Instant)> = Vec::new();
+
+    while running.load(Ordering::SeqCst) {
         let message = connection.poll(10_000)?;
         if let Event::Tracking(data) = message.event() {
-            for hand in data
-                .hands()
-                .iter()
-                .filter(|hand| hand.hand_type() == HandType::Right)
-            {
+            for hand in data.hands().iter() {
                 let now = Instant::now();
 
-                let (leap_x, leap_y, leap_z) = hand_tracking(hand, &mut moving_average);
+                // Left hand: drive the control/modulation layer on its own
+                // channel instead of the melody pipeline.
+                if hand.hand_type() == HandType::Left {
+                    let (_lx, leap_y, leap_z) =
+                        hand_tracking(hand, &mut left_voice.moving_average);
+                    let volume = map_to_midi(leap_y as f32, MIN_Y, MAX_Y, 127.0);
+                    let resonance =
+                        (127.0 * (1.0 - (hand.pinch_distance() / 100.0).clamp(0.0, 1.0))) as u8;
+                    send_midi_cc_on(&mut output_port, left_voice.channel, 7, curve.apply(volume))?;
+                    send_midi_cc_on(&mut output_port, left_voice.channel, 71, resonance)?;
+
+                    // Left-hand depth toggles the arpeggiator (debounced).
+                    if leap_z < ARP_TOGGLE_Z
+                        && now.duration_since(last_arp_toggle).as_millis() >= 500
+                    {
+                        arp_enabled = !arp_enabled;
+                        last_arp_toggle = now;
+                    }
+                    continue;
+                }
+
+                if hand.hand_type() != HandType::Right {
+                    continue;
+                }
+
+                let (leap_x, leap_y, leap_z) = hand_tracking(hand, &mut right_voice.moving_average);
                 let (screen_x, screen_y) =
                     map_leap_coordinates_to_screen(leap_x as f32, leap_y as f32);
 
@@ -244,11 +992,17 @@ fn main() -> Result<(), Box<dyn Error>> {
                 //let speed = (((movement.0.pow(2)) - movement.1.pow(2)) as f64).sqrt();
 
                 let rate = (1.000 - hand.palm().orientation().z().abs()).clamp(0.1, 0.80) as f64;
-                let midi_delay = MIDI_DELAY_MS as f64 * rate;
 
-                if now.duration_since(last_note_time).as_millis() >= midi_delay as u128
-                    && movement != (0, 0)
-                {
+                // Continuous expression: palm roll plus fine X drive pitch bend
+                // on every frame, gliding between the quantized scale notes.
+                let roll = hand.palm().orientation().x();
+                let fine_x = ((leap_x as f32 - MIN_X) / (MAX_X - MIN_X)) * 2.0 - 1.0;
+                let deviation = roll + fine_x * 0.25;
+                pitch_bend.update(&mut output_port, deviation).ok();
+
+                // Compute a candidate note from the current pose and buffer it;
+                // it is played on the next grid tick, not the instant it moves.
+                if movement != (0, 0) {
                     let note = map_to_midi(leap_x as f32, MIN_X, MAX_X as f32, scale.len() as f32);
                     let velocity =
                         map_to_midi(leap_y as f32, MIN_Y, MAX_Y as f32, 127.0);
@@ -309,44 +1063,84 @@ Leap Z {}, Depth    {}
                         - ((velocity as u64 * (max_duration - min_duration))
                             / velocity_range as u64);
 
-                    let velocity = velocity.min(127);
+                    // Shape velocity and expression through the chosen curve.
+                    let velocity = curve.apply(velocity);
+                    let depth = curve.apply(depth);
+
+                    // Arpeggiator (toggled by the left hand) thins the chord to
+                    // its root; otherwise play the full voicing.
+                    let config = if arp_enabled { 0b0010 } else { FULL_CHORD_CONFIG };
 
-                    send_midi_chord_on(
-                        &mut output_port,
-                        nearest_note,
+                    pending = Some(PendingNote {
+                        note: nearest_note,
                         velocity,
+                        depth,
+                        duration,
                         program,
-                        70,
-                        100,
-                        80 as u8,
-                        duration as u8,
-                        &scale
-                    )
-                    .ok();
-                    // Send MIDI CC message for depth
-                    send_midi_cc(&mut output_port, 74, velocity as u8)?; // cutoff
-                    send_midi_cc(&mut output_port, 91, velocity as u8)?; // reverb
-                    send_midi_cc(&mut output_port, 92, duration as u8)?; // reverb
-                    send_midi_cc(&mut output_port, 1, depth as u8)?; // modulation
-
-                    // Store the note and its off-time in the HashMap
-                    active_notes.insert(nearest_note, now + Duration::from_millis(duration));
-                    last_note_time = now;
+                        config,
+                    });
+                }
+
+                // Advance the tempo grid: click on each beat and flush the
+                // buffered note onto the next subdivision boundary.
+                let ticks = beat_clock.advance(now);
+                let beat_crossed = ticks.iter().any(|&(_, is_beat)| is_beat);
+                if beat_crossed {
+                    metronome.click(&mut output_port)?;
+                }
+                if !ticks.is_empty()
+                    && now.duration_since(right_voice.last_note_time) >= beat_clock.tick
+                {
+                    if let Some(p) = pending.take() {
+                        send_midi_chord_on(
+                            &mut output_port,
+                            &mut chord_engine,
+                            p.note,
+                            p.velocity,
+                            p.program,
+                            70,
+                            100,
+                            80 as u8,
+                            p.duration as u8,
+                            p.config,
+                            &scale,
+                        )
+                        .ok();
+                        // Render the same note through the internal synth, if enabled.
+                        if let Some(audio) = &audio {
+                            let mut request =
+                                SamplesRequest::new(p.note, p.velocity, 70, 100, 80, p.duration as u8);
+                            request
+                                .set_hold_time(p.duration as f32 / 1000.0)
+                                .set_volume(p.velocity as f32 / 127.0)
+                                .set_falloff(80.0 / 127.0, p.duration as f32 / 127.0);
+                            audio.trigger(request);
+                            audio_off.push((p.note, now + Duration::from_millis(p.duration)));
+                        }
+
+                        // Send MIDI CC messages for expression
+                        send_midi_cc(&mut output_port, 74, p.velocity)?; // cutoff
+                        send_midi_cc(&mut output_port, 91, p.velocity)?; // reverb
+                        send_midi_cc(&mut output_port, 92, p.duration as u8)?; // reverb
+                        send_midi_cc(&mut output_port, 1, p.depth)?; // modulation
+
+                        right_voice.last_note_time = now;
+                    }
                 }
 
-                // Check and send note-off messages for notes that have expired
-                let notes_to_remove: Vec<u8> = active_notes
-                    .iter()
-                    .filter_map(
-                        |(&note, &off_time)| {
-                            if now >= off_time {
-                                Some(note)
-                            } else {
-                                None
+                // Release audio voices whose note-off time has passed.
+                if audio.is_some() {
+                    audio_off.retain(|&(key, off_time)| {
+                        if now >= off_time {
+                            if let Some(audio) = &audio {
+                                audio.note_off(key);
                             }
-                        },
-                    )
-                    .collect();
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                }
 
                 //GESTURES
                 if hand.pinch_distance() < 10.0
@@ -357,18 +1151,16 @@ Leap Z {}, Depth    {}
                     last_program_change_time = now;
                 }
 
-                for note in notes_to_remove {
-                    send_midi_note_off(&mut output_port, note, 0).ok();
-                    active_notes.remove(&note);
-                }
                 last_hand_position = (leap_x, leap_y);
                 //loading_done = true;
             }
-            if program == 200{break}
-            
         }
     }
-    
+
+    // Release any still-sounding chord voices, then persist the performance.
+    chord_engine.all_notes_off(&mut output_port)?;
+    output_port.save(&record_path)?;
+
     Ok(())
 }
 
@@ -388,3 +1180,79 @@ fn midi_to_note_name(midi_value: u8) -> Option<String> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode one variable-length quantity, returning its value and byte count.
+    fn decode_vlq(bytes: &[u8]) -> (u32, usize) {
+        let mut value = 0u32;
+        let mut i = 0;
+        loop {
+            let byte = bytes[i];
+            value = (value << 7) | (byte & 0x7f) as u32;
+            i += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        (value, i)
+    }
+
+    #[test]
+    fn vlq_round_trips_including_five_byte_deltas() {
+        // The last value needs five 7-bit groups (>= 2^28).
+        for delta in [0u32, 1, 127, 128, 16_383, 16_384, 0x0FFF_FFFF, 300_000_000] {
+            let mut recording = MidiRecording::new();
+            recording.push_delta(delta);
+            let (decoded, len) = decode_vlq(&recording.data);
+            assert_eq!(decoded, delta, "value round-trip for {delta}");
+            assert_eq!(len, recording.data.len(), "no trailing bytes for {delta}");
+        }
+    }
+
+    #[test]
+    fn velocity_curve_linear_is_identity() {
+        let curve = VelocityCurve::Linear;
+        assert_eq!(curve.apply(0), 0);
+        assert_eq!(curve.apply(127), 127);
+    }
+
+    #[test]
+    fn compressive_table_is_monotonic_and_spans_full_range() {
+        let table = compressive_table();
+        assert_eq!(table[0], 0);
+        assert_eq!(table[127], 127);
+        for window in table.windows(2) {
+            assert!(window[1] >= window[0], "table must be non-decreasing");
+        }
+    }
+
+    #[test]
+    fn chord_voice_pitch_clamps_to_scale() {
+        let scale = vec![10u8, 20, 30];
+        assert_eq!(ChordEngine::voice_pitch_for(20, 0, &scale), 20);
+        // Offset past the end clamps to the last scale note.
+        assert_eq!(ChordEngine::voice_pitch_for(20, 10, &scale), 30);
+    }
+
+    #[test]
+    fn beat_clock_counts_ticks_and_beats() {
+        let metronome = Metronome::new(120); // 0.5s per beat
+        let mut clock = BeatClock::new(&metronome, 4); // 0.125s per tick
+        let ticks = clock.advance(clock.start + Duration::from_secs(1));
+        assert_eq!(ticks.len(), 8);
+        assert_eq!(ticks.iter().filter(|&&(_, is_beat)| is_beat).count(), 2);
+    }
+
+    #[test]
+    fn pitch_bend_centres_and_spans() {
+        let mut bend = PitchBend::new(1, 200.0);
+        assert_eq!(bend.as_14bit(), 0x2000);
+        bend.cents = 200.0;
+        assert_eq!(bend.as_14bit(), 0x3fff);
+        bend.cents = -200.0;
+        assert_eq!(bend.as_14bit(), 1);
+    }
+}
+